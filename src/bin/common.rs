@@ -0,0 +1,40 @@
+//! Helpers shared between the `writedisk` and `wd_copier` binaries.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Read a block device's size in bytes from `/sys/block/<name>/size`,
+/// which holds the device's size in 512-byte sectors.
+///
+/// # Errors
+///
+/// Returns an error if `size` can't be read, or its contents aren't a
+/// valid number.
+pub fn read_block_size_bytes(sysfs_block_path: &Path) -> io::Result<u64> {
+    let contents = fs::read_to_string(sysfs_block_path.join("size"))?;
+    let sectors: u64 = contents
+        .trim()
+        .parse()
+        .map_err(|_e| io::Error::new(io::ErrorKind::InvalidData, "invalid size value"))?;
+    Ok(sectors * 512)
+}
+
+/// Format a byte count as a human-readable GiB string, e.g. "28.7 GiB".
+#[allow(clippy::cast_precision_loss)]
+pub fn format_size(bytes: u64) -> String {
+    const GIB: f64 = 1024.0 * 1024.0 * 1024.0;
+    format!("{:.1} GiB", bytes as f64 / GIB)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0.0 GiB");
+        assert_eq!(format_size(1024 * 1024 * 1024), "1.0 GiB");
+        assert_eq!(format_size(30_829_692_928), "28.7 GiB");
+    }
+}
@@ -1,19 +1,34 @@
 #![warn(clippy::pedantic)]
 
+#[path = "common.rs"]
+mod common;
+
 use clap::Parser;
+use common::{format_size, read_block_size_bytes};
 use nix::mount::umount;
 use procfs::Current;
+use sha2::{Digest, Sha256};
 use std::convert::TryInto;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::time::Duration;
 use std::{fs, process, thread};
 
+// BLKRRPART (request 0x125f): ask the kernel to re-read a block
+// device's partition table.
+nix::ioctl_none!(ioctl_blkrrpart, 0x12, 95);
+
 #[derive(Debug, Parser)]
 struct Opt {
     src: PathBuf,
     dst: PathBuf,
+
+    /// Read the destination back after writing and verify it matches
+    /// what was written.
+    #[arg(long)]
+    verify: bool,
 }
 
 /// Get OS dirty byte count using [`procfs::Meminfo`].
@@ -76,8 +91,9 @@ fn sync_progress_bar(
     rx: &mpsc::Receiver<()>,
     mut progress_bar: progress::Bar,
     mut dirty: DirtyInfo,
+    total_stages: u32,
 ) {
-    progress_bar.set_job_title("syncing... (2/2)");
+    progress_bar.set_job_title(&format!("syncing... (2/{total_stages})"));
     loop {
         dirty.current = get_dirty_bytes();
         progress_bar.reach_percent(dirty.calc_sync_percent());
@@ -91,6 +107,82 @@ fn sync_progress_bar(
     }
 }
 
+/// Get the `/sys/block/<name>` sysfs path for a `/dev/<name>` block
+/// device path.
+fn sysfs_block_path(device: &Path) -> Option<PathBuf> {
+    device
+        .file_name()
+        .map(|name| Path::new("/sys/block").join(name))
+}
+
+/// Returns true if a device of `dst_size` bytes is too small to hold
+/// an image of `src_size` bytes.
+fn is_too_small(dst_size: u64, src_size: u64) -> bool {
+    dst_size < src_size
+}
+
+/// Hash a single chunk with SHA-256.
+fn hash_chunk(chunk: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(chunk);
+    hasher.finalize().into()
+}
+
+/// Ask the kernel to re-read `dst`'s partition table via the
+/// `BLKRRPART` ioctl, so newly written partitions show up without a
+/// replug.
+fn reread_partition_table(dst: &fs::File) -> nix::Result<()> {
+    unsafe { ioctl_blkrrpart(dst.as_raw_fd()) }.map(|_ret| ())
+}
+
+/// Search upwards for the directory representing the physical USB
+/// device itself (as opposed to an interface or host-controller
+/// node), identified by the presence of an `authorized` file together
+/// with an `idVendor` file (interfaces have the former but not the
+/// latter).
+fn find_usb_device_path(path: &Path) -> Option<PathBuf> {
+    for path in path.ancestors() {
+        if path.join("authorized").exists() && path.join("idVendor").exists() {
+            return Some(path.into());
+        }
+    }
+    None
+}
+
+/// Fall back to resetting the physical USB device by briefly
+/// deauthorizing and reauthorizing it, which forces Linux to redo
+/// enumeration of the device (including its partitions).
+fn usb_reset(dst: &Path) -> io::Result<()> {
+    let sysfs_block = sysfs_block_path(dst)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not a /dev block device path"))?;
+    let device_path = sysfs_block.join("device").canonicalize()?;
+    let usb_device_path = find_usb_device_path(&device_path).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "USB device node not found")
+    })?;
+
+    let authorized_path = usb_device_path.join("authorized");
+    fs::write(&authorized_path, b"0")?;
+    fs::write(&authorized_path, b"1")?;
+    Ok(())
+}
+
+/// Ask the kernel to re-read the partition table of `dst`, falling
+/// back to a USB-level device reset if that fails. Either way,
+/// failure here is non-fatal: it just means the user may need to
+/// replug the device to see freshly written partitions.
+fn rescan_partitions(dst: &fs::File, dst_path: &Path) {
+    match reread_partition_table(dst) {
+        Ok(()) => println!("re-read partition table"),
+        Err(e) => {
+            eprintln!("BLKRRPART failed ({e}), falling back to a USB device reset");
+            match usb_reset(dst_path) {
+                Ok(()) => println!("reset USB device"),
+                Err(e) => eprintln!("USB device reset failed (non-fatal): {e}"),
+            }
+        }
+    }
+}
+
 fn unmount_all_partitions(device: &Path) {
     // Unmount all partitions mounted for the selected device.
     let device_name =
@@ -135,6 +227,24 @@ fn main() {
     let mut src = fs::File::open(opt.src).unwrap();
     let src_size = src.metadata().unwrap().len();
 
+    if let Some(sysfs_path) = sysfs_block_path(&opt.dst) {
+        match read_block_size_bytes(&sysfs_path) {
+            Ok(dst_size) if is_too_small(dst_size, src_size) => {
+                eprintln!(
+                    "{} ({}) is too small for the image ({})",
+                    opt.dst.display(),
+                    format_size(dst_size),
+                    format_size(src_size)
+                );
+                process::exit(1);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("failed to read size of {}: {e}", opt.dst.display());
+            }
+        }
+    }
+
     let open_result = fs::OpenOptions::new().write(true).open(&opt.dst);
     let mut dst = match open_result {
         Ok(fh) => fh,
@@ -148,13 +258,18 @@ fn main() {
         }
     };
 
+    let total_stages: u32 = if opt.verify { 3 } else { 2 };
+
     let mut progress_bar = progress::Bar::new();
-    progress_bar.set_job_title("copying... (1/2)");
+    progress_bar.set_job_title(&format!("copying... (1/{total_stages})"));
 
     let mut remaining = src_size;
     let mut bytes_written: u64 = 0;
     let chunk_size: u64 = 1024 * 1024; // TODO
     let mut buf = Vec::new();
+    // One SHA-256 digest per chunk, computed while writing, so the
+    // verify pass below doesn't need to re-read the source file.
+    let mut chunk_hashes = Vec::new();
     while remaining > 0 {
         let percent = calc_percent(bytes_written, src_size);
         progress_bar.reach_percent(percent);
@@ -169,6 +284,10 @@ fn main() {
         src.read_exact(&mut buf).unwrap();
         dst.write_all(&buf).unwrap();
 
+        if opt.verify {
+            chunk_hashes.push(hash_chunk(&buf));
+        }
+
         remaining -= read_size;
         bytes_written += read_size;
     }
@@ -178,19 +297,74 @@ fn main() {
 
     // If we can't get dirty bytes info we can just print 'syncing...' to the screen
     if dirty.after_copy == 0 {
-        println!("syncing... (2/2)");
+        println!("syncing... (2/{total_stages})");
     } else {
         thread::spawn(move || {
-            sync_progress_bar(&rx, progress_bar, dirty);
+            sync_progress_bar(&rx, progress_bar, dirty, total_stages);
         });
     }
 
     dst.sync_data().unwrap();
     tx.send(()).unwrap();
 
+    // Verify before rescanning partitions: the USB-reset fallback in
+    // rescan_partitions() can make the device disappear and
+    // re-enumerate, which would otherwise race with (or invalidate)
+    // the read-back below.
+    if opt.verify {
+        verify_write(&opt.dst, src_size, chunk_size, &chunk_hashes, total_stages);
+    }
+
+    rescan_partitions(&dst, &opt.dst);
+
     println!("finished");
 }
 
+/// Reopen `dst` and read back `src_size` bytes, comparing the SHA-256
+/// digest of each chunk against the digest computed for the
+/// corresponding chunk during the write pass. Exits non-zero on
+/// mismatch, reporting the start offset of the first chunk whose digest
+/// differs (not the exact offset of the first differing byte).
+fn verify_write(
+    dst: &Path,
+    src_size: u64,
+    chunk_size: u64,
+    chunk_hashes: &[[u8; 32]],
+    total_stages: u32,
+) {
+    let mut progress_bar = progress::Bar::new();
+    progress_bar.set_job_title(&format!("verifying... ({total_stages}/{total_stages})"));
+
+    let mut dst = fs::File::open(dst).unwrap();
+    let mut remaining = src_size;
+    let mut bytes_read: u64 = 0;
+    let mut buf = Vec::new();
+    let mut chunk_index = 0;
+    while remaining > 0 {
+        let percent = calc_percent(bytes_read, src_size);
+        progress_bar.reach_percent(percent);
+
+        let read_size = if chunk_size > remaining {
+            remaining
+        } else {
+            chunk_size
+        };
+        buf.resize(read_size.try_into().unwrap(), 0);
+        dst.read_exact(&mut buf).unwrap();
+
+        if hash_chunk(&buf) != chunk_hashes[chunk_index] {
+            eprintln!("verification failed: data mismatch at offset {bytes_read}");
+            process::exit(1);
+        }
+
+        remaining -= read_size;
+        bytes_read += read_size;
+        chunk_index += 1;
+    }
+
+    println!("verified");
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -229,4 +403,20 @@ mod tests {
         dirty.current = 200;
         assert_eq!(dirty.calc_sync_percent(), 0);
     }
+
+    #[test]
+    fn test_is_too_small() {
+        assert!(is_too_small(10, 20));
+        assert!(!is_too_small(20, 20));
+        assert!(!is_too_small(30, 20));
+    }
+
+    #[test]
+    fn test_hash_chunk() {
+        let a = hash_chunk(b"hello");
+        let b = hash_chunk(b"hello");
+        let c = hash_chunk(b"world");
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
 }
@@ -1,18 +1,50 @@
 #![warn(clippy::pedantic)]
 
+#[path = "common.rs"]
+mod common;
+
 use clap::Parser;
+use common::{format_size, read_block_size_bytes};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::{env, fs, process};
+use std::time::Duration;
+use std::{env, fs, process, thread};
 
 #[derive(Clone, Debug)]
 struct UsbBlockDevice {
     /// The device path, e.g. "/dev/sdc"
     path: PathBuf,
 
-    manufacturer: String,
-    product: String,
-    serial: String,
+    id_vendor: String,
+    id_product: String,
+
+    manufacturer: Option<String>,
+    product: Option<String>,
+    serial: Option<String>,
+
+    size_bytes: u64,
+    removable: bool,
+}
+
+/// A handful of common USB storage vendor IDs, used to give a friendlier
+/// display name when a device exposes none of the `manufacturer`,
+/// `product`, and `serial` sysfs files.
+const KNOWN_VENDORS: &[(&str, &str)] = &[
+    ("0781", "SanDisk"),
+    ("0951", "Kingston"),
+    ("090c", "Silicon Motion"),
+    ("0930", "Toshiba"),
+    ("154b", "PNY"),
+    ("8564", "Transcend"),
+    ("1f75", "Innostor"),
+    ("05e3", "Genesys Logic"),
+];
+
+fn lookup_vendor_name(id_vendor: &str) -> Option<&'static str> {
+    KNOWN_VENDORS
+        .iter()
+        .find(|(id, _)| id.eq_ignore_ascii_case(id_vendor))
+        .map(|(_, name)| *name)
 }
 
 /// Try to determine whether this is a USB device or not by searching
@@ -29,14 +61,15 @@ fn is_usb_in_path(path: &Path) -> bool {
     false
 }
 
-/// Search upwards for a directory containing device info
-/// (manufacturer, product, and serial).
+/// Search upwards for a directory containing device info.
+///
+/// `idVendor` and `idProduct` always exist on a USB device node, so
+/// those are required. `manufacturer`, `product`, and `serial` are
+/// read later if present, but many real devices don't expose all
+/// (or any) of them, so they aren't required here.
 fn find_usb_info(path: &Path) -> Option<PathBuf> {
     for path in path.ancestors() {
-        if path.join("manufacturer").exists()
-            && path.join("product").exists()
-            && path.join("serial").exists()
-        {
+        if path.join("idVendor").exists() && path.join("idProduct").exists() {
             return Some(path.into());
         }
     }
@@ -44,7 +77,14 @@ fn find_usb_info(path: &Path) -> Option<PathBuf> {
 }
 
 impl UsbBlockDevice {
-    fn get_all() -> io::Result<Vec<UsbBlockDevice>> {
+    /// Find all USB block devices.
+    ///
+    /// By default, USB-attached disks that report themselves as
+    /// non-removable (e.g. some USB-SATA bridges and NVMe enclosures)
+    /// are excluded, since they're often internal system drives and
+    /// accidentally overwriting one would be disastrous. Pass
+    /// `include_non_removable` to include them too.
+    fn get_all(include_non_removable: bool) -> io::Result<Vec<UsbBlockDevice>> {
         let mut result = Vec::new();
         for entry in fs::read_dir("/sys/block")? {
             let entry = entry?;
@@ -65,35 +105,155 @@ impl UsbBlockDevice {
                 continue;
             }
 
+            let removable = fs::read_to_string(path.join("removable"))
+                .map(|contents| contents.trim() == "1")
+                .unwrap_or(false);
+            if !removable && !include_non_removable {
+                continue;
+            }
+
             if let Some(info_path) = find_usb_info(&device_path) {
                 let read = |name| -> io::Result<String> {
                     let path = info_path.join(name);
                     let contents = fs::read_to_string(path)?;
                     Ok(contents.trim().into())
                 };
+                let read_optional = |name| -> Option<String> {
+                    fs::read_to_string(info_path.join(name))
+                        .ok()
+                        .map(|contents| contents.trim().into())
+                };
 
-                result.push(UsbBlockDevice {
-                    path: Path::new("/dev").join(entry.file_name()),
-                    manufacturer: read("manufacturer")?,
-                    product: read("product")?,
-                    serial: read("serial")?,
-                });
+                // A freshly-hotplugged device's sysfs attributes may not
+                // all be populated yet, especially while polling for new
+                // devices with --wait. Rather than letting one
+                // incompletely-enumerated device turn into a hard error
+                // for the whole scan, just skip it; it'll show up once
+                // it's fully there.
+                let device = (|| -> io::Result<UsbBlockDevice> {
+                    Ok(UsbBlockDevice {
+                        path: Path::new("/dev").join(entry.file_name()),
+                        id_vendor: read("idVendor")?,
+                        id_product: read("idProduct")?,
+                        manufacturer: read_optional("manufacturer"),
+                        product: read_optional("product"),
+                        serial: read_optional("serial"),
+                        size_bytes: read_block_size_bytes(&path)?,
+                        removable,
+                    })
+                })();
+                if let Ok(device) = device {
+                    result.push(device);
+                }
             }
         }
         Ok(result)
     }
 
     fn full_name(&self) -> String {
-        format!("{} {} {}", &self.manufacturer, &self.product, &self.serial)
+        let parts: Vec<&str> = [
+            self.manufacturer.as_deref(),
+            self.product.as_deref(),
+            self.serial.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        if parts.is_empty() {
+            self.vendor_product_id_string()
+        } else {
+            parts.join(" ")
+        }
+    }
+
+    /// Fall back to a `vvvv:pppp` vendor:product string (decoded
+    /// against a small bundled vendor list where possible) for
+    /// devices that expose none of `manufacturer`, `product`, and
+    /// `serial`.
+    fn vendor_product_id_string(&self) -> String {
+        let id = format!("{}:{}", self.id_vendor, self.id_product);
+        match lookup_vendor_name(&self.id_vendor) {
+            Some(name) => format!("{name} ({id})"),
+            None => id,
+        }
+    }
+}
+
+/// Print a prominent warning before writing to a non-removable disk.
+fn warn_non_removable(device: &UsbBlockDevice) {
+    eprintln!(
+        "WARNING: {} ({}, {}) does not report itself as removable. \
+         It may be an internal system drive. Proceeding because \
+         --all-disks was given.",
+        device.path.display(),
+        device.full_name(),
+        format_size(device.size_bytes)
+    );
+}
+
+/// Returns true if `after` contains a device whose path isn't in `before`.
+fn has_new_device(before: &[PathBuf], after: &[UsbBlockDevice]) -> bool {
+    after.iter().any(|device| !before.contains(&device.path))
+}
+
+/// Poll every 500ms until a USB block device not present in `before`
+/// appears, then return the freshly read device list.
+///
+/// If `device_name` is given, keeps polling until the new device's
+/// `full_name()` matches it, rather than stopping at the first new
+/// device found.
+fn wait_for_new_device(
+    before: &[UsbBlockDevice],
+    all_disks: bool,
+    device_name: Option<&String>,
+) -> Vec<UsbBlockDevice> {
+    println!("waiting for a USB disk...");
+    let before_paths: Vec<PathBuf> = before.iter().map(|device| device.path.clone()).collect();
+    loop {
+        thread::sleep(Duration::from_millis(500));
+        let devices = UsbBlockDevice::get_all(all_disks).unwrap();
+
+        let found = match device_name {
+            Some(device_name) => devices.iter().any(|device| {
+                !before_paths.contains(&device.path) && device.full_name() == *device_name
+            }),
+            None => has_new_device(&before_paths, &devices),
+        };
+        if found {
+            return devices;
+        }
     }
 }
 
-fn choose_device(device_name: Option<&String>) -> UsbBlockDevice {
-    let devices = UsbBlockDevice::get_all().unwrap();
+fn choose_device(device_name: Option<&String>, all_disks: bool, wait: bool) -> UsbBlockDevice {
+    let mut devices = UsbBlockDevice::get_all(all_disks).unwrap();
 
     if devices.is_empty() {
-        println!("no devices found");
-        process::exit(1);
+        if !wait {
+            println!("no devices found");
+            process::exit(1);
+        }
+
+        devices = wait_for_new_device(&devices, all_disks, device_name);
+
+        // Only auto-select here when no device name was requested; if
+        // one was given, fall through to the name-matching logic below
+        // so the device actually gets checked against it instead of
+        // blindly writing to whatever showed up.
+        if device_name.is_none() && devices.len() == 1 {
+            let device = &devices[0];
+            println!(
+                "found new device: [{}] {} ({})",
+                device.path.display(),
+                device.full_name(),
+                format_size(device.size_bytes)
+            );
+            if !device.removable {
+                warn_non_removable(device);
+            }
+            return device.clone();
+        }
     }
 
     if let Some(device_name) = device_name {
@@ -102,10 +262,14 @@ fn choose_device(device_name: Option<&String>) -> UsbBlockDevice {
             .find(|device| device.full_name() == *device_name)
         {
             println!(
-                "writing to {} ({})",
+                "writing to {} ({}, {})",
                 device.path.display(),
-                device.full_name()
+                device.full_name(),
+                format_size(device.size_bytes)
             );
+            if !device.removable {
+                warn_non_removable(device);
+            }
             return device.clone();
         }
 
@@ -115,9 +279,10 @@ fn choose_device(device_name: Option<&String>) -> UsbBlockDevice {
 
     for (index, device) in devices.iter().enumerate() {
         println!(
-            "{index}: [{path}] {name}",
+            "{index}: [{path}] {name} ({size})",
             path = device.path.display(),
-            name = device.full_name()
+            name = device.full_name(),
+            size = format_size(device.size_bytes)
         );
     }
 
@@ -139,7 +304,11 @@ fn choose_device(device_name: Option<&String>) -> UsbBlockDevice {
         process::exit(1);
     }
 
-    devices[index].clone()
+    let device = &devices[index];
+    if !device.removable {
+        warn_non_removable(device);
+    }
+    device.clone()
 }
 
 #[derive(Debug, Parser)]
@@ -157,6 +326,24 @@ struct Opt {
     /// the tool to be used non-interactively.
     #[arg(long)]
     device_name: Option<String>,
+
+    /// Also list non-removable USB-attached disks (e.g. USB-SATA
+    /// bridges and some NVMe enclosures).
+    ///
+    /// These are excluded by default because they may be internal
+    /// system drives, and overwriting one would be disastrous.
+    #[arg(long)]
+    all_disks: bool,
+
+    /// After writing, read the destination back and verify it matches
+    /// what was written.
+    #[arg(long)]
+    verify: bool,
+
+    /// Instead of erroring out when no USB disks are found, wait for
+    /// one to be plugged in.
+    #[arg(long)]
+    wait: bool,
 }
 
 fn main() {
@@ -168,7 +355,7 @@ fn main() {
         process::exit(1);
     }
 
-    let device = choose_device(opt.device_name.as_ref());
+    let device = choose_device(opt.device_name.as_ref(), opt.all_disks, opt.wait);
 
     let copier_path = env::current_exe()
         .expect("failed to get current exe path")
@@ -177,17 +364,83 @@ fn main() {
         .join("wd_copier");
 
     println!(
-        "sudo {} {} {}",
+        "sudo {} {} {}{}",
         copier_path.display(),
         opt.input.display(),
-        device.path.display()
+        device.path.display(),
+        if opt.verify { " --verify" } else { "" }
     );
-    let status = process::Command::new("sudo")
-        .args([&copier_path, &opt.input, &device.path])
-        .status()
-        .expect("failed to run command");
+    let mut command = process::Command::new("sudo");
+    command.args([&copier_path, &opt.input, &device.path]);
+    if opt.verify {
+        command.arg("--verify");
+    }
+    let status = command.status().expect("failed to run command");
     if !status.success() {
         println!("copy failed");
         process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_device() -> UsbBlockDevice {
+        UsbBlockDevice {
+            path: PathBuf::from("/dev/sdc"),
+            id_vendor: "0781".into(),
+            id_product: "5581".into(),
+            manufacturer: None,
+            product: None,
+            serial: None,
+            size_bytes: 0,
+            removable: true,
+        }
+    }
+
+    #[test]
+    fn test_full_name_with_all_fields() {
+        let mut device = test_device();
+        device.manufacturer = Some("Samsung".into());
+        device.product = Some("PSSD T7".into());
+        device.serial = Some("S1SLVX2T1210".into());
+        assert_eq!(device.full_name(), "Samsung PSSD T7 S1SLVX2T1210");
+    }
+
+    #[test]
+    fn test_full_name_with_only_product() {
+        // Some devices expose a product string but no manufacturer or
+        // serial; that partial information is still more useful than
+        // falling all the way back to the vendor:product ID string.
+        let mut device = test_device();
+        device.product = Some("USB Flash Drive".into());
+        assert_eq!(device.full_name(), "USB Flash Drive");
+    }
+
+    #[test]
+    fn test_full_name_falls_back_to_vendor_product_id() {
+        let device = test_device();
+        assert_eq!(device.full_name(), "SanDisk (0781:5581)");
+    }
+
+    #[test]
+    fn test_vendor_product_id_string_unknown_vendor() {
+        let mut device = test_device();
+        device.id_vendor = "ffff".into();
+        device.id_product = "0001".into();
+        assert_eq!(device.vendor_product_id_string(), "ffff:0001");
+    }
+
+    #[test]
+    fn test_has_new_device() {
+        let mut sda = test_device();
+        sda.path = PathBuf::from("/dev/sda");
+        let mut sdb = test_device();
+        sdb.path = PathBuf::from("/dev/sdb");
+
+        let before = vec![sda.path.clone()];
+        assert!(!has_new_device(&before, &[sda.clone()]));
+        assert!(has_new_device(&before, &[sda, sdb]));
+    }
+}
@@ -86,7 +86,10 @@ impl Vm {
                 self.usb_backing_file
             ),
             "-device nec-usb-xhci,id=xhci",
-            "-device usb-storage,bus=xhci.0,drive=stick",
+            // QEMU's usb-storage device defaults to non-removable, but
+            // writedisk only lists removable disks by default, so mark
+            // it removable to keep this test working.
+            "-device usb-storage,bus=xhci.0,drive=stick,removable=true",
         ]
         .join(" ");
 